@@ -2,11 +2,14 @@ extern crate termion;
 extern crate tui;
 extern crate git2;
 extern crate colored;
+extern crate notify;
 
 use std::{io, env, thread, time, vec};
+use std::path::Path;
 use std::sync::mpsc;
 use termion::event;
 use termion::input::TermRead;
+use notify::{RecommendedWatcher, Watcher, RecursiveMode};
 
 use tui::Terminal;
 use tui::backend::TermionBackend;
@@ -18,22 +21,35 @@ type Term = Terminal<TermBackend>;
 
 use colored::*;
 
-use git2::{Repository, BranchType, Status};
+use git2::{Repository, BranchType, Status, DescribeOptions, DescribeFormatOptions, DiffOptions, DiffFormat};
+use git2::build::CheckoutBuilder;
 use git2::{
-    STATUS_IGNORED,
+    STATUS_IGNORED, STATUS_CONFLICTED,
     STATUS_INDEX_TYPECHANGE, STATUS_INDEX_NEW, STATUS_INDEX_MODIFIED, STATUS_INDEX_DELETED, STATUS_INDEX_RENAMED,
     STATUS_WT_TYPECHANGE, STATUS_WT_NEW, STATUS_WT_MODIFIED, STATUS_WT_DELETED, STATUS_WT_RENAMED
 };
 
 enum Event {
     Input(event::Key),
-    Tick
+    Tick,
+    Repo
 }
 
 struct HeadInfo {
     ref_name: String,
     hash: String,
-    message: String
+    message: String,
+    ahead: usize,
+    behind: usize,
+    has_upstream: bool,
+    detached: bool
+}
+
+struct BranchInfo {
+    name: String,
+    ahead: usize,
+    behind: usize,
+    has_upstream: bool
 }
 
 struct StatusEntry {
@@ -41,14 +57,31 @@ struct StatusEntry {
     status: Status
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum EntryKind {
+    Conflicted,
+    Untracked,
+    Unstaged,
+    Staged
+}
+
+struct SelectableEntry {
+    kind: EntryKind,
+    path: String
+}
+
 struct App {
     terminal: Term,
     repo: Option<Repository>,
     head: Option<HeadInfo>,
-    branches: vec::Vec<String>,
+    branches: vec::Vec<BranchInfo>,
     untracked: vec::Vec<String>,
     unstaged: vec::Vec<StatusEntry>,
     staged: vec::Vec<StatusEntry>,
+    conflicted: vec::Vec<StatusEntry>,
+    stashes: vec::Vec<String>,
+    entries: vec::Vec<SelectableEntry>,
+    selected: usize,
     term_size: Rect,
     rx: mpsc::Receiver<Event>,
     refresh: bool
@@ -61,7 +94,7 @@ fn main() {
     app.run();
 }
 
-fn init_events() -> mpsc::Receiver<Event> {
+fn init_events(watch_path: Option<String>) -> mpsc::Receiver<Event> {
     let (tx, rx) = mpsc::channel();
     let input_tx = tx.clone();
 
@@ -81,12 +114,74 @@ fn init_events() -> mpsc::Receiver<Event> {
         }
     });
 
+    if let Some(path) = watch_path {
+        let repo_tx = tx.clone();
+        thread::spawn(move || {
+            let (watch_tx, watch_rx) = mpsc::channel();
+            let mut watcher: RecommendedWatcher = match Watcher::new_raw(watch_tx) {
+                Ok(watcher) => watcher,
+                Err(_) => return
+            };
+            let _ = watcher.watch(&path, RecursiveMode::Recursive);
+            let _ = watcher.watch(Path::new(&path).join(".git"), RecursiveMode::Recursive);
+
+            loop {
+                if watch_rx.recv().is_err() {
+                    break;
+                }
+                // Drain any burst of events (e.g. a large checkout) into a single refresh.
+                while watch_rx.try_recv().is_ok() {}
+                if repo_tx.send(Event::Repo).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
     rx
 }
 
+fn divergence_label(ahead: usize, behind: usize) -> String {
+    if ahead > 0 && behind > 0 {
+        "\u{21d5}".to_string()
+    } else if ahead > 0 {
+        format!("\u{21e1}{}", ahead)
+    } else if behind > 0 {
+        format!("\u{21e3}{}", behind)
+    } else {
+        String::default()
+    }
+}
+
+fn ahead_behind(repo: &Repository, branch: &git2::Branch) -> Result<(usize, usize), git2::Error> {
+    let upstream = branch.upstream()?;
+    let local_oid = branch.get().target().ok_or_else(|| git2::Error::from_str("branch has no target"))?;
+    let upstream_oid = upstream.get().target().ok_or_else(|| git2::Error::from_str("upstream has no target"))?;
+    repo.graph_ahead_behind(local_oid, upstream_oid)
+}
+
+fn describe_detached(repo: &Repository, head: &git2::Reference) -> String {
+    let mut opts = DescribeOptions::new();
+    opts.describe_tags();
+    if let Ok(describe) = repo.describe(&opts) {
+        if let Ok(label) = describe.format(None) {
+            return label;
+        }
+        let mut fmt_opts = DescribeFormatOptions::new();
+        fmt_opts.abbreviated_size(7);
+        if let Ok(label) = describe.format(Some(&fmt_opts)) {
+            return label;
+        }
+    }
+    match head.target() {
+        Some(oid) => format!("{}", oid).chars().take(7).collect(),
+        None => String::default()
+    }
+}
+
 impl App {
     fn new(path: Option<String>) -> App {
-        let rx = init_events();
+        let rx = init_events(path.clone());
         let terminal = Terminal::new(TermBackend::new().unwrap()).unwrap();
         let size = terminal.size().unwrap();
 
@@ -99,6 +194,10 @@ impl App {
             untracked: Vec::new(),
             unstaged: Vec::new(),
             staged: Vec::new(),
+            conflicted: Vec::new(),
+            stashes: Vec::new(),
+            entries: Vec::new(),
+            selected: 0,
             rx: rx,
             refresh: true
         };
@@ -117,6 +216,100 @@ impl App {
         }
     }
 
+    fn stage_selected(&mut self) {
+        let entry = match self.entries.get(self.selected) {
+            Some(entry) if entry.kind == EntryKind::Unstaged || entry.kind == EntryKind::Untracked => entry,
+            _ => return
+        };
+        let path = entry.path.clone();
+        if let Some(ref repo) = self.repo {
+            if let Ok(mut index) = repo.index() {
+                if index.add_path(Path::new(&path)).is_ok() {
+                    let _ = index.write();
+                    self.refresh = true;
+                }
+            }
+        }
+    }
+
+    fn unstage_selected(&mut self) {
+        let entry = match self.entries.get(self.selected) {
+            Some(entry) if entry.kind == EntryKind::Staged => entry,
+            _ => return
+        };
+        let path = entry.path.clone();
+        if let Some(ref repo) = self.repo {
+            if let Ok(head) = repo.head() {
+                if let Some(target) = head.target() {
+                    if let Ok(head_object) = repo.find_object(target, None) {
+                        if repo.reset_default(Some(&head_object), &[path]).is_ok() {
+                            self.refresh = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn discard_selected(&mut self) {
+        let entry = match self.entries.get(self.selected) {
+            Some(entry) if entry.kind == EntryKind::Unstaged => entry,
+            _ => return
+        };
+        let path = entry.path.clone();
+        if let Some(ref repo) = self.repo {
+            let mut checkout = CheckoutBuilder::new();
+            checkout.path(&path);
+            checkout.force();
+            if repo.checkout_head(Some(&mut checkout)).is_ok() {
+                self.refresh = true;
+            }
+        }
+    }
+
+    fn diff_for_selected(&self) -> String {
+        let entry = match self.entries.get(self.selected) {
+            Some(entry) => entry,
+            None => return String::default()
+        };
+        let repo = match self.repo {
+            Some(ref repo) => repo,
+            None => return String::default()
+        };
+
+        let mut opts = DiffOptions::new();
+        opts.pathspec(&entry.path);
+
+        let diff = match entry.kind {
+            EntryKind::Unstaged => repo.diff_index_to_workdir(None, Some(&mut opts)),
+            EntryKind::Staged => {
+                let tree = repo.head().ok()
+                    .and_then(|head| head.target())
+                    .and_then(|oid| repo.find_commit(oid).ok())
+                    .and_then(|commit| commit.tree().ok());
+                repo.diff_tree_to_index(tree.as_ref(), None, Some(&mut opts))
+            },
+            EntryKind::Conflicted | EntryKind::Untracked => return String::default()
+        };
+        let diff = match diff {
+            Ok(diff) => diff,
+            Err(_) => return String::default()
+        };
+
+        let mut text = String::new();
+        let _ = diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
+            let content = String::from_utf8_lossy(line.content());
+            let rendered = match line.origin() {
+                '+' => format!("+{}", content).green().to_string(),
+                '-' => format!("-{}", content).red().to_string(),
+                _ => content.into_owned()
+            };
+            text.push_str(&rendered);
+            true
+        });
+        text
+    }
+
     fn update_size(&mut self) {
         let size = self.terminal.size().unwrap();
         if size != self.term_size {
@@ -141,10 +334,32 @@ impl App {
                     event::Key::Char('r') => {
                         self.refresh = true;
                     }
+                    event::Key::Char('j') | event::Key::Down => {
+                        if self.selected + 1 < self.entries.len() {
+                            self.selected += 1;
+                        }
+                    }
+                    event::Key::Char('k') | event::Key::Up => {
+                        if self.selected > 0 {
+                            self.selected -= 1;
+                        }
+                    }
+                    event::Key::Char('s') => {
+                        self.stage_selected();
+                    }
+                    event::Key::Char('u') => {
+                        self.unstage_selected();
+                    }
+                    event::Key::Char('x') => {
+                        self.discard_selected();
+                    }
                     _ => {}
                 }
                 Event::Tick => {
                 }
+                Event::Repo => {
+                    self.refresh = true;
+                }
             }
 
             self.draw();
@@ -163,24 +378,50 @@ impl App {
         output.push_str("rngit\n");
         if self.repo.is_some() && self.refresh {
             let repo = self.repo.take();
-            let repo = repo.unwrap();
+            let mut repo = repo.unwrap();
             {
-                let head = repo.head().unwrap();
-                let head_commit = repo.find_commit(head.target().unwrap());
-                let head_short = head.shorthand();
                 self.head.take();
                 let head_info = self.head.get_or_insert(HeadInfo {
                     hash: String::default(),
                     message: String::default(),
-                    ref_name: String::default()
+                    ref_name: String::default(),
+                    ahead: 0,
+                    behind: 0,
+                    has_upstream: false,
+                    detached: false
                 });
-                if head_short.is_some() {
-                    head_info.ref_name = head_short.unwrap().to_string();
-                }
-                if head_commit.is_ok() {
-                    let head_commit = head_commit.unwrap();
-                    head_info.message = head_commit.message().unwrap().to_string();
-                    head_info.hash = format!("{}", head_commit.id());
+
+                match repo.head() {
+                    Ok(head) => {
+                        if head.is_branch() {
+                            if let Some(name) = head.shorthand() {
+                                head_info.ref_name = name.to_string();
+                                if let Ok(branch) = repo.find_branch(name, BranchType::Local) {
+                                    if let Ok((ahead, behind)) = ahead_behind(&repo, &branch) {
+                                        head_info.ahead = ahead;
+                                        head_info.behind = behind;
+                                        head_info.has_upstream = true;
+                                    }
+                                }
+                            }
+                        } else {
+                            head_info.detached = true;
+                            head_info.ref_name = describe_detached(&repo, &head);
+                        }
+
+                        if let Some(target) = head.target() {
+                            if let Ok(head_commit) = repo.find_commit(target) {
+                                head_info.message = head_commit.message().unwrap_or_default().to_string();
+                                head_info.hash = format!("{}", head_commit.id());
+                            }
+                        }
+                    },
+                    Err(e) if e.code() == git2::ErrorCode::UnbornBranch => {
+                        head_info.message = "No commits yet".to_string();
+                    },
+                    Err(e) => {
+                        output.push_str(e.message());
+                    }
                 }
             }
             {
@@ -191,9 +432,19 @@ impl App {
                     Ok(branches) => {
                         for branch in branches {
                             if branch.is_ok() {
-                                let name = branch.unwrap().0;
-                                let name = name.name();
-                                self.branches.push(name.unwrap().unwrap().to_string());
+                                let branch = branch.unwrap().0;
+                                let name = branch.name();
+                                let name = name.unwrap().unwrap().to_string();
+                                let (ahead, behind, has_upstream) = match ahead_behind(&repo, &branch) {
+                                    Ok((ahead, behind)) => (ahead, behind, true),
+                                    Err(_) => (0, 0, false)
+                                };
+                                self.branches.push(BranchInfo {
+                                    name: name,
+                                    ahead: ahead,
+                                    behind: behind,
+                                    has_upstream: has_upstream
+                                });
                             }
                         }
                     },
@@ -206,6 +457,7 @@ impl App {
                 self.staged.clear();
                 self.unstaged.clear();
                 self.untracked.clear();
+                self.conflicted.clear();
 
                 let statuses = repo.statuses(Option::None);
                 match statuses {
@@ -216,7 +468,12 @@ impl App {
                                 continue;
                             }
                             let path = status.path().unwrap();
-                            if stat.intersects(STATUS_INDEX_TYPECHANGE | STATUS_INDEX_NEW | STATUS_INDEX_MODIFIED | STATUS_INDEX_DELETED | STATUS_INDEX_RENAMED) {
+                            if stat.intersects(STATUS_CONFLICTED) {
+                                self.conflicted.push(StatusEntry {
+                                    status: stat,
+                                    path: path.to_string()
+                                });
+                            } else if stat.intersects(STATUS_INDEX_TYPECHANGE | STATUS_INDEX_NEW | STATUS_INDEX_MODIFIED | STATUS_INDEX_DELETED | STATUS_INDEX_RENAMED) {
                                 self.staged.push(StatusEntry {
                                     status: stat,
                                     path: path.to_string()
@@ -236,13 +493,46 @@ impl App {
                     }
                 }
             }
+            {
+                let mut stashes = Vec::new();
+                let _ = repo.stash_foreach(|index, message, _oid| {
+                    stashes.push(format!("stash@{{{}}}: {}", index, message));
+                    true
+                });
+                self.stashes = stashes;
+            }
+            {
+                self.entries.clear();
+                for status in &self.conflicted {
+                    self.entries.push(SelectableEntry { kind: EntryKind::Conflicted, path: status.path.clone() });
+                }
+                for path in &self.untracked {
+                    self.entries.push(SelectableEntry { kind: EntryKind::Untracked, path: path.clone() });
+                }
+                for status in &self.unstaged {
+                    self.entries.push(SelectableEntry { kind: EntryKind::Unstaged, path: status.path.clone() });
+                }
+                for status in &self.staged {
+                    self.entries.push(SelectableEntry { kind: EntryKind::Staged, path: status.path.clone() });
+                }
+                if self.selected >= self.entries.len() {
+                    self.selected = self.entries.len().saturating_sub(1);
+                }
+            }
             self.repo.get_or_insert(repo);
             self.refresh = false;
         }
         if self.head.is_some() {
             output.push_str("Head: ");
             let head = self.head.take().unwrap();
+            if head.detached {
+                output.push_str("detached at ");
+            }
             output.push_str(&head.ref_name.bright_blue());
+            if head.has_upstream {
+                output.push(' ');
+                output.push_str(&divergence_label(head.ahead, head.behind));
+            }
             output.push(' ');
             output.push_str(&head.message);
             output.push('\n');
@@ -252,13 +542,30 @@ impl App {
             output.push_str("Branches:\n");
             for branch in &self.branches {
                 output.push('\t');
-                output.push_str(&branch);
+                output.push_str(&branch.name);
+                if branch.has_upstream {
+                    output.push(' ');
+                    output.push_str(&divergence_label(branch.ahead, branch.behind));
+                }
+                output.push('\n');
+            }
+        }
+        let mut entry_idx = 0usize;
+        if !self.conflicted.is_empty() {
+            output.push_str("\nConflicts:\n");
+            for status in &self.conflicted {
+                output.push_str(if entry_idx == self.selected { "> " } else { "  " });
+                entry_idx += 1;
+                output.push_str("both modified:  ");
+                output.push_str(&status.path);
                 output.push('\n');
             }
         }
         if !self.untracked.is_empty() {
             output.push_str("\nUntracked changes:\n");
             for status in &self.untracked {
+                output.push_str(if entry_idx == self.selected { "> " } else { "  " });
+                entry_idx += 1;
                 output.push_str(status);
                 output.push('\n');
             }
@@ -266,6 +573,8 @@ impl App {
         if !self.unstaged.is_empty() {
             output.push_str("\nUnstaged changes:\n");
             for status in &self.unstaged {
+                output.push_str(if entry_idx == self.selected { "> " } else { "  " });
+                entry_idx += 1;
                 if status.status.intersects(STATUS_WT_MODIFIED) {
                     output.push_str("modified: ");
                 } else if status.status.intersects(STATUS_WT_DELETED) {
@@ -282,6 +591,8 @@ impl App {
         if !self.staged.is_empty() {
             output.push_str("\nStaged changes:\n");
             for status in &self.staged {
+                output.push_str(if entry_idx == self.selected { "> " } else { "  " });
+                entry_idx += 1;
                 if status.status.intersects(STATUS_INDEX_MODIFIED) {
                     output.push_str("modified: ");
                 } else if status.status.intersects(STATUS_INDEX_DELETED) {
@@ -295,6 +606,16 @@ impl App {
                 output.push('\n');
             }
         }
+        if !self.stashes.is_empty() {
+            output.push_str("\nStashes:\n");
+            for stash in &self.stashes {
+                output.push_str(stash);
+                output.push('\n');
+            }
+        }
+
+        let diff_text = self.diff_for_selected();
+        let t = &mut self.terminal;
 
         Group::default()
             .direction(Direction::Vertical)
@@ -302,13 +623,18 @@ impl App {
             .render(t, &self.term_size, |t, chunks| {
                 Group::default()
                     .direction(Direction::Horizontal)
-                    .sizes(&[Size::Percent(100)])
+                    .sizes(&[Size::Percent(50), Size::Percent(50)])
                     .render(t, &chunks[0], |t, chunks| {
                         Paragraph::default()
                             .text(
                                 output.as_str(),
                             )
                             .render(t, &chunks[0]);
+                        Paragraph::default()
+                            .text(
+                                diff_text.as_str(),
+                            )
+                            .render(t, &chunks[1]);
                     });
             });
 